@@ -6,16 +6,109 @@ use std::path::{Path, PathBuf};
 use std::fs::{File, OpenOptions};
 use std::fs;
 use std::io::{Read, Write};
-use md5::compute;
 use std::time::Duration;
 use reqwest;
 use serde_json::Value;
 use async_std::task;
+use sha2::{Digest, Sha256};
+use ed25519_dalek::{Signature, VerifyingKey};
+use base64::Engine as _;
+use serde::Deserialize;
 use wasmtime::{
     component::{bindgen, Component, Linker, ResourceTable},
     Config, Engine, Result, Store,
 };
 
+/// Shared async HTTP client for the update pipeline, reused across requests
+/// instead of opening a new connection pool per call.
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: std::sync::OnceLock<reqwest::Client> = std::sync::OnceLock::new();
+    CLIENT.get_or_init(reqwest::Client::new)
+}
+
+const CONFIG_PATH: &str = "../config.json";
+
+/// One update server the device will try, in priority order.
+#[derive(Debug, Clone, Deserialize)]
+struct UpdateSource {
+    url: String,
+    #[serde(default)]
+    important: bool,
+}
+
+/// Device-wide updater configuration, loaded from [`CONFIG_PATH`] instead of
+/// the `localhost:8080` / `../staging` / `../active` literals this started
+/// out with, so a deployment doesn't need a recompile to point somewhere real.
+#[derive(Debug, Clone, Deserialize)]
+struct UpdaterConfig {
+    device_id: String,
+    #[serde(default = "default_staging_dir")]
+    staging_dir: String,
+    #[serde(default = "default_active_dir")]
+    active_dir: String,
+    #[serde(default = "default_chunk_dir")]
+    chunk_dir: String,
+    #[serde(default = "default_poll_interval_secs")]
+    poll_interval_secs: u64,
+    #[serde(default)]
+    trusted_keys: Vec<String>,
+    sources: Vec<UpdateSource>,
+}
+
+fn default_staging_dir() -> String { "../staging".to_string() }
+fn default_active_dir() -> String { "../active".to_string() }
+fn default_chunk_dir() -> String { "../chunks".to_string() }
+fn default_poll_interval_secs() -> u64 { 20 }
+
+/// Used when no config file is present, so the updater keeps working against
+/// a local dev server out of the box.
+fn default_config() -> UpdaterConfig {
+    UpdaterConfig {
+        device_id: "unknown".to_string(),
+        staging_dir: default_staging_dir(),
+        active_dir: default_active_dir(),
+        chunk_dir: default_chunk_dir(),
+        poll_interval_secs: default_poll_interval_secs(),
+        trusted_keys: Vec::new(),
+        sources: vec![UpdateSource { url: "http://localhost:8080".to_string(), important: true }],
+    }
+}
+
+fn load_config() -> anyhow::Result<UpdaterConfig> {
+    let contents = fs::read_to_string(CONFIG_PATH).context("reading config file")?;
+    serde_json::from_str(&contents).context("parsing config file")
+}
+
+/// A source that couldn't be used this round, recorded instead of aborting so
+/// the remaining sources still get a chance.
+#[derive(Debug)]
+struct ConfigError {
+    url: String,
+    reason: String,
+    important: bool,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "source {} ({}): {}",
+            self.url,
+            if self.important { "important" } else { "optional" },
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Ed25519 public key pinned into the binary. It is the only key trusted when
+/// the config lists no `trusted_keys`; when it lists any, only those are used.
+const TRUSTED_PUBLIC_KEY: [u8; 32] = [
+    0x3b, 0x6a, 0x27, 0xbc, 0xce, 0xb6, 0xa4, 0x2d, 0x62, 0xa3, 0xa8, 0xd0, 0x2a, 0x6f, 0x0d, 0x73,
+    0x65, 0x32, 0x15, 0x77, 0x1d, 0xe2, 0x43, 0xa6, 0x3a, 0xc0, 0x48, 0xa1, 0x8b, 0x59, 0xda, 0x29,
+];
+
 // Generate bindings of the guest and host components.
 bindgen!({
     world: "blink",
@@ -24,12 +117,19 @@ bindgen!({
         "sketch:embedded/delay/delay": Delay,
         "sketch:embedded/digital/input-pin": InputPin,
         "sketch:embedded/digital/output-pin": OutputPin,
+        "sketch:embedded/digital/stateful-output-pin": StatefulOutputPin,
     },
 });
 
 pub struct Delay;
 pub struct InputPin(CdevPin);
 pub struct OutputPin(CdevPin);
+/// An output pin that also remembers the last level it was set to, so
+/// `is_set_high`/`is_set_low`/`toggle` can answer without reading the line back.
+pub struct StatefulOutputPin {
+    pin: CdevPin,
+    state: embedded_hal::digital::PinState,
+}
 
 struct HostComponent {
     table: ResourceTable,
@@ -63,16 +163,40 @@ impl digital::HostInputPin for HostComponent {
 
     fn wait_for_high(
         &mut self,
-        _self_: wasmtime::component::Resource<digital::InputPin>,
+        self_: wasmtime::component::Resource<digital::InputPin>,
     ) -> wasmtime::Result<Result<(), digital::ErrorCode>> {
-        todo!("InputPin::wait_for_high")
+        let pin = self.table.get_mut(&self_)?;
+        if embedded_hal::digital::InputPin::is_high(&mut pin.0).unwrap_or(false) {
+            return Ok(Ok(()));
+        }
+        let mut events = pin.0.line().events(
+            LineRequestFlags::INPUT,
+            EventRequestFlags::RISING_EDGE,
+            "hello-embedded",
+        )?;
+        match events.next() {
+            Some(Ok(_)) => Ok(Ok(())),
+            _ => Ok(Err(digital::ErrorCode::Other)),
+        }
     }
 
     fn wait_for_low(
         &mut self,
-        _self_: wasmtime::component::Resource<digital::InputPin>,
+        self_: wasmtime::component::Resource<digital::InputPin>,
     ) -> wasmtime::Result<Result<(), digital::ErrorCode>> {
-        todo!("InputPin::wait_for_low")
+        let pin = self.table.get_mut(&self_)?;
+        if embedded_hal::digital::InputPin::is_low(&mut pin.0).unwrap_or(false) {
+            return Ok(Ok(()));
+        }
+        let mut events = pin.0.line().events(
+            LineRequestFlags::INPUT,
+            EventRequestFlags::FALLING_EDGE,
+            "hello-embedded",
+        )?;
+        match events.next() {
+            Some(Ok(_)) => Ok(Ok(())),
+            _ => Ok(Err(digital::ErrorCode::Other)),
+        }
     }
 
     fn wait_for_rising_edge(
@@ -185,23 +309,36 @@ impl digital::HostOutputPin for HostComponent {
 impl digital::HostStatefulOutputPin for HostComponent {
     fn is_set_high(
         &mut self,
-        _self_: wasmtime::component::Resource<digital::StatefulOutputPin>,
+        self_: wasmtime::component::Resource<digital::StatefulOutputPin>,
     ) -> wasmtime::Result<Result<bool, digital::ErrorCode>> {
-        todo!("StatefulOutputLin::is_set_high")
+        let self_ = self.table.get_mut(&self_)?;
+        Ok(Ok(self_.state == embedded_hal::digital::PinState::High))
     }
 
     fn is_set_low(
         &mut self,
-        _self_: wasmtime::component::Resource<digital::StatefulOutputPin>,
+        self_: wasmtime::component::Resource<digital::StatefulOutputPin>,
     ) -> wasmtime::Result<Result<bool, digital::ErrorCode>> {
-        todo!("StatefulOutputLin::is_set_low")
+        let self_ = self.table.get_mut(&self_)?;
+        Ok(Ok(self_.state == embedded_hal::digital::PinState::Low))
     }
 
     fn toggle(
         &mut self,
-        _self_: wasmtime::component::Resource<digital::StatefulOutputPin>,
+        self_: wasmtime::component::Resource<digital::StatefulOutputPin>,
     ) -> wasmtime::Result<Result<(), digital::ErrorCode>> {
-        todo!("StatefulOutputLin::toggle")
+        let self_ = self.table.get_mut(&self_)?;
+        let new_state = match self_.state {
+            embedded_hal::digital::PinState::High => embedded_hal::digital::PinState::Low,
+            embedded_hal::digital::PinState::Low => embedded_hal::digital::PinState::High,
+        };
+        match embedded_hal::digital::OutputPin::set_state(&mut self_.pin, new_state) {
+            Ok(()) => {
+                self_.state = new_state;
+                Ok(Ok(()))
+            }
+            Err(_) => Ok(Err(digital::ErrorCode::Other)),
+        }
     }
 
     fn drop(
@@ -234,11 +371,71 @@ struct MyState {
     host: HostComponent,
 }
 
+const ACTIVE_PLUGIN_NAME: &str = "plugin.wasm";
+const ACTIVE_BACKUP_NAME: &str = "plugin.wasm.bak";
+/// How many consecutive run failures a freshly-activated plugin gets before
+/// it's considered bad and rolled back to the previous known-good version.
+const MAX_CONSECUTIVE_RUN_FAILURES: u32 = 3;
+
+static CURRENT_VERSION: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+static PREVIOUS_VERSION: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+static FAILED_VERSIONS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+static CONSECUTIVE_RUN_FAILURES: std::sync::Mutex<u32> = std::sync::Mutex::new(0);
+static ROLLBACK_COUNT: std::sync::Mutex<u32> = std::sync::Mutex::new(0);
+
+/// Guards the active/backup slot swap as a whole. `update_plugin` (background
+/// poller) and `rollback_active_plugin` (main run loop) both read-modify-write
+/// `plugin.wasm`/`plugin.wasm.bak` plus the version statics across several
+/// steps; without this, the two could interleave and leave the backup slot
+/// holding a bad plugin instead of the last known-good one.
+static ACTIVE_SLOT_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Restores the previous known-good plugin into the active dir, discards the bad
+/// staged file, and records the failed version so it won't be re-downloaded.
+fn rollback_active_plugin(config: &UpdaterConfig) {
+    let _guard = ACTIVE_SLOT_LOCK.lock().unwrap();
+
+    let active_path = Path::new(&config.active_dir);
+    let backup_path = active_path.join(ACTIVE_BACKUP_NAME);
+    let active_plugin_path = active_path.join(ACTIVE_PLUGIN_NAME);
+
+    if backup_path.exists() {
+        match fs::copy(&backup_path, &active_plugin_path) {
+            Ok(_) => println!("Rolled back to previous known-good plugin"),
+            Err(err) => eprintln!("Error restoring backup plugin: {}", err),
+        }
+    } else {
+        eprintln!("No backup plugin available to roll back to");
+    }
+
+    let failed_version = CURRENT_VERSION.lock().unwrap().take();
+    if let Some(failed_version) = failed_version.clone() {
+        FAILED_VERSIONS.lock().unwrap().push(failed_version);
+    }
+    *CURRENT_VERSION.lock().unwrap() = PREVIOUS_VERSION.lock().unwrap().clone();
+    *CONSECUTIVE_RUN_FAILURES.lock().unwrap() = 0;
+    *ROLLBACK_COUNT.lock().unwrap() += 1;
+
+    if let Some(failed_version) = failed_version {
+        cleanup_staged_file(config, &format!("plugin_{}", failed_version));
+    } else {
+        // No version was on record as active; fall back to discarding
+        // whatever staged file is newest, same as the pre-rollback default.
+        cleanup(config);
+    }
+}
+
 fn main() -> Result<()> {
+    let config = load_config().unwrap_or_else(|err| {
+        eprintln!("Error loading config ({}), using built-in defaults", err);
+        default_config()
+    });
+
+    let poller_config = config.clone();
     task::spawn(async move {
         loop {
-            task::sleep(Duration::from_secs(20)).await;
-            check_update_available().await;
+            task::sleep(Duration::from_secs(poller_config.poll_interval_secs)).await;
+            check_update_available(&poller_config).await;
         }
     });
     // Create the engine and the linker.
@@ -248,7 +445,7 @@ fn main() -> Result<()> {
     Blink::add_to_linker(&mut linker, |state: &mut MyState| &mut state.host)?;
     loop {
     // Read the guest component file.
-    let plugins = get_plugins_from_path("../active")?;
+    let plugins = get_plugins_from_path(&config.active_dir)?;
     if plugins.is_empty() {
         println!("No file present");
         return Ok(())
@@ -276,18 +473,46 @@ fn main() -> Result<()> {
             "write-output",
         )?)?;
 
+        // Request pin 27 as the stateful output, separate from pin 17's plain
+        // OutputPin so a guest can use toggle()/is_set_high()/is_set_low()
+        // without disturbing the non-stateful LED.
+        let stateful_output = CdevPin::new(chip.get_line(27)?.request(
+            LineRequestFlags::OUTPUT,
+            0,
+            "write-stateful-output",
+        )?)?;
+
         // Create the resources we'll pass into the `run` function.
         let led = my_state.host.table.push(OutputPin(output))?;
+        let stateful_led = my_state.host.table.push(StatefulOutputPin {
+            pin: stateful_output,
+            state: embedded_hal::digital::PinState::Low,
+        })?;
         let delay = my_state.host.table.push(Delay)?;
 
         // Create the store and instantiate the component.
         let mut store = Store::new(&engine, my_state);
-        let (blink, _instance) = Blink::instantiate(&mut store, &component, &linker)?;
+        let run_result = Blink::instantiate(&mut store, &component, &linker).and_then(|(blink, _instance)| {
+            blink.sketch_embedded_run().call_run(&mut store, led, stateful_led, delay)
+        });
 
-        // Run!
-        blink
-            .sketch_embedded_run()
-            .call_run(&mut store, led, delay)?;
+        // Run! If the new plugin fails to instantiate or trap-runs, count it
+        // against it and roll back to the previous known-good plugin once it
+        // has failed too many times in a row.
+        match run_result {
+            Ok(()) => {
+                *CONSECUTIVE_RUN_FAILURES.lock().unwrap() = 0;
+            }
+            Err(err) => {
+                eprintln!("Plugin run failed: {}", err);
+                let mut failures = CONSECUTIVE_RUN_FAILURES.lock().unwrap();
+                *failures += 1;
+                if *failures >= MAX_CONSECUTIVE_RUN_FAILURES {
+                    drop(failures);
+                    rollback_active_plugin(&config);
+                }
+            }
+        }
     }
     }
 }
@@ -308,10 +533,10 @@ fn get_plugins_from_path(path: &str) -> anyhow::Result<Vec<PathBuf>> {
     Ok(plugins)
 }
 
-async fn check_update_available() {
-    let available = fetch_parse_input();
+async fn check_update_available(config: &UpdaterConfig) {
+    let available = fetch_parse_input(config).await;
     if available {
-        if let Err(err) = update_plugin() {
+        if let Err(err) = update_plugin(config) {
             eprintln!("Error updating file: {}", err);
         }
     } else {
@@ -319,114 +544,351 @@ async fn check_update_available() {
     }
 }
 
-fn update_plugin() -> Result<(), std::io::Error> {
-    let file_name = find_latest_version("../staging");
-    let staging_path = Path::new("../staging");
-    let active_path = Path::new("../active");
+fn update_plugin(config: &UpdaterConfig) -> Result<(), std::io::Error> {
+    let _guard = ACTIVE_SLOT_LOCK.lock().unwrap();
+
+    let file_name = find_latest_version(&config.staging_dir);
+    let staging_path = Path::new(&config.staging_dir);
+    let active_path = Path::new(&config.active_dir);
     let file_path = staging_path.join(file_name.clone());
         if file_path.exists() {
-            fs::copy(file_path, active_path.join("plugin.wasm"))?;
+            let active_plugin_path = active_path.join(ACTIVE_PLUGIN_NAME);
+            if active_plugin_path.exists() {
+                // Only refresh the backup slot from a currently-active plugin
+                // that has actually run clean so far. Otherwise the active
+                // plugin could itself be mid-way to a rollback (accumulating
+                // failures, just hasn't hit MAX_CONSECUTIVE_RUN_FAILURES yet),
+                // and copying it over the backup would clobber the last
+                // known-good version with an unproven one.
+                if *CONSECUTIVE_RUN_FAILURES.lock().unwrap() == 0 {
+                    fs::copy(&active_plugin_path, active_path.join(ACTIVE_BACKUP_NAME))?;
+                    let previous = CURRENT_VERSION.lock().unwrap().clone();
+                    *PREVIOUS_VERSION.lock().unwrap() = previous;
+                } else {
+                    println!("Active plugin hasn't cleared its failure count yet; leaving backup slot untouched");
+                }
+            }
+
+            fs::copy(file_path, &active_plugin_path)?;
+            *CURRENT_VERSION.lock().unwrap() = Some(trim_version(&file_name));
+            *CONSECUTIVE_RUN_FAILURES.lock().unwrap() = 0;
             Ok(())
         } else {
             Err(std::io::Error::new(std::io::ErrorKind::NotFound, "File not found"))
         }
 }
 
-fn cleanup() {
-    let latest_file = find_latest_version("../staging");
-    match fs::remove_file(format!("{}/{}","../staging",latest_file)) {
+fn cleanup(config: &UpdaterConfig) {
+    let latest_file = find_latest_version(&config.staging_dir);
+    cleanup_staged_file(config, &latest_file);
+}
+
+/// Deletes a specific staged file by name, as opposed to [`cleanup`] which
+/// always targets whatever is newest-by-semver in the staging directory.
+/// Needed by rollback, where the plugin that actually failed may no longer
+/// be the newest file staged (the poller can have staged a newer, unrelated
+/// version in the time it took to hit the consecutive-failure threshold).
+fn cleanup_staged_file(config: &UpdaterConfig, file_name: &str) {
+    match fs::remove_file(format!("{}/{}", config.staging_dir, file_name)) {
         Ok(_) => println!("File at fault deleted successfully"),
         Err(e) => eprintln!("Error: {}", e),
     }
 }
 
-fn fetch_parse_input() -> bool {
-    let ip_address = "localhost";                   // change ip address
-    let file_name = find_latest_version("../staging");
-    let version = trim_version(&file_name);
+/// Tries each configured source in priority order (important sources first)
+/// and checks for an update there. A malformed or unreachable source is
+/// recorded as a [`ConfigError`] rather than aborting the whole check.
+async fn fetch_parse_input(config: &UpdaterConfig) -> bool {
+    // Report what's actually active, not whatever happens to be sitting in
+    // staging (the two can diverge right after a rollback). Before the first
+    // activation there's nothing in CURRENT_VERSION yet, so fall back to
+    // whatever's staged.
+    let version = CURRENT_VERSION.lock().unwrap().clone().unwrap_or_else(|| {
+        trim_version(&find_latest_version(&config.staging_dir))
+    });
+    let rollback_count = *ROLLBACK_COUNT.lock().unwrap();
 
-    let url = format!("http://{}:8080/checkupdate?current_version={}", ip_address, version);
-    let response = match reqwest::blocking::get(url){
-        Ok(response) => response,
-        Err(err) => {
-            println!("Error fetching CHECK-UPDATE URL: {}", err);
-            return false
-        }
-    };
-    let json_data = match response.text() {
-        Ok(text) => text,
-        Err(err) => {
-            println!("Error parsing response: {}", err);
-            return false
-        }
-    };
-    let json_data = json_data.trim();
-    let data: Value = match serde_json::from_str(&json_data) {
-        Ok(data) => data,
-        Err(err) => {
-            println!("Error parsing JSON: {}", err);
-            return false
+    let mut sources: Vec<&UpdateSource> = config.sources.iter().collect();
+    sources.sort_by_key(|source| std::cmp::Reverse(source.important));
+
+    let mut errors = Vec::new();
+    for source in sources {
+        match try_update_source(config, source, &version, rollback_count).await {
+            Ok(update_ready) => return update_ready,
+            Err(reason) => errors.push(ConfigError {
+                url: source.url.clone(),
+                reason,
+                important: source.important,
+            }),
         }
-    };
+    }
 
-    let latest_version = data["latest_version"].as_str().unwrap();
-    let download_url = data["download_url"].as_str().unwrap();
+    for error in &errors {
+        eprintln!("Update source failed: {}", error);
+    }
+    false
+}
+
+/// Checks a single source for an update, downloading, staging and verifying
+/// it if one is available. `Ok(true)` means a verified update is staged and
+/// ready to activate; `Ok(false)` means the source was reachable but had
+/// nothing usable; `Err` means the source itself was malformed/unreachable.
+async fn try_update_source(
+    config: &UpdaterConfig,
+    source: &UpdateSource,
+    version: &str,
+    rollback_count: u32,
+) -> Result<bool, String> {
+    let url = format!(
+        "{}/checkupdate?device_id={}&current_version={}&rollback_count={}",
+        source.url, config.device_id, version, rollback_count
+    );
+    let response = http_client().get(&url).send().await.map_err(|err| format!("fetching checkupdate: {}", err))?;
+    let json_data = response.text().await.map_err(|err| format!("reading response: {}", err))?;
+    let data: Value = serde_json::from_str(json_data.trim()).map_err(|err| format!("parsing JSON: {}", err))?;
+
+    let latest_version = data["latest_version"].as_str().ok_or("missing latest_version")?;
+    if FAILED_VERSIONS.lock().unwrap().iter().any(|v| v == latest_version) {
+        println!("Skipping previously-failed version: {}", latest_version);
+        return Ok(false);
+    }
+
+    let download_url = data["download_url"].as_str().ok_or("missing download_url")?;
     if !download_url.is_empty() {
-        match download_file(ip_address, latest_version) {
-            Ok(_) => (),
-            Err(err) => println!("Error downloading file: {}", err),
+        if let Err(err) = download_file_delta(&source.url, latest_version, config).await {
+            println!("Delta download unavailable ({}), falling back to full download", err);
+            download_file(&source.url, latest_version, config)
+                .await
+                .map_err(|err| format!("downloading file: {}", err))?;
         }
     }
-    let check_sum = data["checksum"].as_str().unwrap();
-    let latest_file = capture_filename_from_header(ip_address, latest_version);
-    if is_valid_input(check_sum, latest_file.as_str()) {
-        if is_wasm_file(latest_file.as_str()) {
-            return true
+
+    let check_sum = data["checksum"].as_str().ok_or("missing checksum")?;
+    let signature = data["signature"].as_str().ok_or("missing signature")?;
+    let latest_file = capture_filename_from_header(&source.url, latest_version).await;
+    if is_valid_input(check_sum, signature, latest_file.as_str(), config) {
+        if is_wasm_file(latest_file.as_str(), &config.staging_dir) {
+            Ok(true)
         } else {
-            cleanup();
-            return false
+            cleanup(config);
+            Ok(false)
         }
-    } else { cleanup(); return false }
+    } else {
+        cleanup(config);
+        Ok(false)
+    }
 }
 
-fn download_file(ip_address: &str, version: &str) -> Result<(), reqwest::Error> {
-    // Construct the URL using the provided IP address and version
-    let file_name = capture_filename_from_header(ip_address, version);
+/// Streams the plugin body to `<staging_dir>/<file>` in chunks instead of buffering
+/// the whole response, and resumes a previous partial download via an HTTP
+/// `Range` request when possible. Runs entirely against the async `reqwest`
+/// client so a large download never blocks the executor thread.
+async fn download_file(source_url: &str, version: &str, config: &UpdaterConfig) -> anyhow::Result<()> {
+    // Construct the URL using the source's base URL and version
+    let file_name = capture_filename_from_header(source_url, version).await;
     if file_name.is_empty() { return Ok(()) }
-    let url = format!("http://{}:8080/download?version={}", ip_address, version);
-    let response = match reqwest::blocking::get(url) {
-        Ok(response) => response,
-        Err(err) => {
-            return Err(err)
-        }
-    };
-    if response.status().is_success() {
-        let mut file = OpenOptions::new()
+    let staging_path = format!("{}/{}", config.staging_dir, &file_name);
+
+    let already_downloaded = fs::metadata(&staging_path).map(|meta| meta.len()).unwrap_or(0);
+
+    let url = format!("{}/download?version={}", source_url, version);
+    let mut request = http_client().get(&url);
+    if already_downloaded > 0 {
+        request = request.header("Range", format!("bytes={}-", already_downloaded));
+    }
+    let mut response = request.send().await.context("sending download request")?;
+
+    let resuming = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    if !resuming && already_downloaded > 0 {
+        // Server doesn't support (or ignored) the range request; start over.
+        println!("Server does not support resume, restarting download: {}", file_name);
+    }
+
+    if !response.status().is_success() {
+        println!("Failed to download file: {}", response.status());
+        return Ok(());
+    }
+
+    let total_len = response
+        .content_length()
+        .map(|len| if resuming { len + already_downloaded } else { len });
+
+    let mut file = OpenOptions::new()
         .write(true)
         .create(true)
-        .open(format!("{}/{}", "../staging", &file_name))
-        .expect("Failed to open file");
+        .append(resuming)
+        .truncate(!resuming)
+        .open(&staging_path)
+        .context("failed to open staging file")?;
+
+    let mut received = if resuming { already_downloaded } else { 0 };
+    while let Some(chunk) = response.chunk().await.context("reading download stream")? {
+        file.write_all(&chunk).context("writing staging file")?;
+        received += chunk.len() as u64;
+        match total_len {
+            Some(total) if total > 0 => {
+                println!("Downloading {}: {}/{} bytes ({:.0}%)", file_name, received, total, (received as f64 / total as f64) * 100.0);
+            }
+            _ => println!("Downloading {}: {} bytes", file_name, received),
+        }
+    }
+
+    println!("File downloaded successfully: {}", file_name);
+    Ok(())
+}
 
-        let body = response.bytes()?;
-        let _ = file.write_all(&body);
+const CHUNK_MIN_SIZE: usize = 4 * 1024;
+const CHUNK_MAX_SIZE: usize = 64 * 1024;
+/// Cut a chunk boundary once the low bits of the rolling hash are all zero;
+/// with these bits this averages ~16 KiB chunks.
+const CHUNK_BOUNDARY_MASK: u64 = (16 * 1024 - 1) as u64;
 
-        println!("File downloaded successfully: {}", file_name);
-    } else {
-        println!("Failed to download file: {}", response.status());
+/// Lazily-built table of pseudo-random multipliers used by the Gear rolling
+/// hash in [`chunk_data`]. Deterministic so the same bytes always chunk the
+/// same way, which is what lets client and server agree on chunk boundaries.
+fn gear_table() -> &'static [u64; 256] {
+    static TABLE: std::sync::OnceLock<[u64; 256]> = std::sync::OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut table = [0u64; 256];
+        let mut seed: u64 = 0x9E3779B97F4A7C15;
+        for entry in table.iter_mut() {
+            seed = seed.wrapping_add(0x9E3779B97F4A7C15);
+            let mut z = seed;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+            *entry = z ^ (z >> 31);
+        }
+        table
+    })
+}
+
+/// Splits `data` into content-defined chunks with a Gear rolling hash instead
+/// of fixed-size slices, so inserting or removing bytes in one place only
+/// shifts the chunk(s) around the edit instead of every chunk after it.
+fn chunk_data(data: &[u8]) -> Vec<&[u8]> {
+    let table = gear_table();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut hash: u64 = 0;
+
+    for i in 0..data.len() {
+        hash = (hash << 1).wrapping_add(table[data[i] as usize]);
+        let len = i + 1 - start;
+        if len >= CHUNK_MIN_SIZE && (hash & CHUNK_BOUNDARY_MASK == 0 || len >= CHUNK_MAX_SIZE) {
+            chunks.push(&data[start..=i]);
+            start = i + 1;
+            hash = 0;
+        }
+    }
+    if start < data.len() {
+        chunks.push(&data[start..]);
     }
+    chunks
+}
+
+fn digest_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+fn chunk_store_path(chunk_dir: &str, digest: &str) -> PathBuf {
+    Path::new(chunk_dir).join(digest)
+}
+
+fn has_chunk(chunk_dir: &str, digest: &str) -> bool {
+    chunk_store_path(chunk_dir, digest).exists()
+}
+
+fn store_chunk(chunk_dir: &str, digest: &str, bytes: &[u8]) -> std::io::Result<()> {
+    fs::create_dir_all(chunk_dir)?;
+    fs::write(chunk_store_path(chunk_dir, digest), bytes)
+}
+
+fn load_chunk(chunk_dir: &str, digest: &str) -> std::io::Result<Vec<u8>> {
+    fs::read(chunk_store_path(chunk_dir, digest))
+}
+
+/// Fetches the ordered list of chunk digests that make up `version`.
+async fn fetch_chunk_manifest(source_url: &str, version: &str) -> anyhow::Result<Vec<String>> {
+    let url = format!("{}/manifest?version={}", source_url, version);
+    let response = http_client().get(url).send().await.context("fetching chunk manifest")?;
+    let data: Value = response.json().await.context("parsing chunk manifest")?;
+    let digests = data["chunks"]
+        .as_array()
+        .context("manifest missing chunks array")?
+        .iter()
+        .filter_map(|v| v.as_str().map(str::to_string))
+        .collect();
+    Ok(digests)
+}
+
+async fn fetch_chunk(source_url: &str, digest: &str) -> anyhow::Result<Vec<u8>> {
+    let url = format!("{}/chunk?digest={}", source_url, digest);
+    let response = http_client().get(url).send().await.context("fetching chunk")?;
+    let bytes = response.bytes().await.context("reading chunk body")?.to_vec();
+    let actual = digest_hex(&bytes);
+    if actual != digest {
+        anyhow::bail!("chunk digest mismatch: expected {}, got {}", digest, actual);
+    }
+    Ok(bytes)
+}
+
+/// Reassembles `version` from content-addressed chunks instead of
+/// re-downloading the whole file: only chunks missing from the local chunk
+/// store are fetched, the rest come from chunks already seen in a previous version.
+async fn download_file_delta(source_url: &str, version: &str, config: &UpdaterConfig) -> anyhow::Result<()> {
+    let file_name = capture_filename_from_header(source_url, version).await;
+    if file_name.is_empty() { return Ok(()) }
+
+    let manifest = fetch_chunk_manifest(source_url, version).await?;
+    let mut assembled = Vec::new();
+    for digest in &manifest {
+        let bytes = if has_chunk(&config.chunk_dir, digest) {
+            load_chunk(&config.chunk_dir, digest).context("reading cached chunk")?
+        } else {
+            let bytes = fetch_chunk(source_url, digest).await?;
+            store_chunk(&config.chunk_dir, digest, &bytes).context("caching downloaded chunk")?;
+            bytes
+        };
+        assembled.extend_from_slice(&bytes);
+    }
+
+    // Re-chunk the assembled file so any chunk that wasn't already in the
+    // store (e.g. it arrived merged differently) is available for next time.
+    for chunk in chunk_data(&assembled) {
+        let digest = digest_hex(chunk);
+        if !has_chunk(&config.chunk_dir, &digest) {
+            let _ = store_chunk(&config.chunk_dir, &digest, chunk);
+        }
+    }
+
+    fs::write(format!("{}/{}", config.staging_dir, &file_name), &assembled)
+        .context("writing reassembled file to staging")?;
+    println!("File reassembled from {} chunks: {}", manifest.len(), file_name);
     Ok(())
 }
 
-fn capture_filename_from_header(ip_address: &str, version: &str) -> String {
-    let url = format!("http://{}:8080/download?version={}", ip_address, version);
-    let response = match reqwest::blocking::get(url) {
+async fn capture_filename_from_header(source_url: &str, version: &str) -> String {
+    let url = format!("{}/download?version={}", source_url, version);
+    let response = match http_client().get(url).send().await {
         Ok(response) => response,
         Err(err) => {
             println!("Error fetching URL: {}", err);
             return "".to_string()
         }
     };
-    let filename = response.headers().get("Content-Disposition").unwrap().to_str().unwrap();
+    let filename = match response.headers().get("Content-Disposition") {
+        Some(value) => match value.to_str() {
+            Ok(value) => value,
+            Err(err) => {
+                println!("Error reading Content-Disposition header: {}", err);
+                return "".to_string()
+            }
+        },
+        None => {
+            println!("Error: response missing Content-Disposition header");
+            return "".to_string()
+        }
+    };
     let filename = filename.split("=").last().unwrap().trim_matches('"');
     filename.to_string()
 }
@@ -467,21 +929,57 @@ fn find_latest_version(dir_path: &str) -> String {
     }
 }
 
-fn is_valid_input(check_sum: &str, file_name: &str) -> bool {
-    match File::open(format!("../staging/{}", file_name)){
+/// Verifies the staged file against both the reported SHA-256 digest (corruption
+/// check) and an Ed25519 signature over the exact bytes written to the staging dir
+/// (authenticity check). Both must pass before the file may ever be activated.
+fn is_valid_input(check_sum: &str, signature: &str, file_name: &str, config: &UpdaterConfig) -> bool {
+    let buffer = match File::open(format!("{}/{}", config.staging_dir, file_name)) {
         Ok(mut file) => {
-                let mut buffer = Vec::new();
-                let _ = file.read_to_end(&mut buffer);
-                let md5_sum = compute(&buffer);
-                let md5_sum = format!("{:x}", md5_sum);
-                md5_sum == check_sum
-            },
-        Err(_) => false,
+            let mut buffer = Vec::new();
+            if file.read_to_end(&mut buffer).is_err() {
+                return false;
+            }
+            buffer
+        }
+        Err(_) => return false,
+    };
+
+    let digest = Sha256::digest(&buffer);
+    if format!("{:x}", digest) != check_sum {
+        return false;
     }
+
+    verify_signature(&buffer, signature, &config.trusted_keys)
+}
+
+/// Checks `bytes` against `signature` (base64 of a 64-byte Ed25519 signature)
+/// using `trusted_keys` (base64-encoded 32-byte public keys) if configured,
+/// falling back to the pinned `TRUSTED_PUBLIC_KEY` otherwise.
+fn verify_signature(bytes: &[u8], signature: &str, trusted_keys: &[String]) -> bool {
+    let signature_bytes = match base64::engine::general_purpose::STANDARD.decode(signature) {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    let signature_bytes: [u8; 64] = match signature_bytes.try_into() {
+        Ok(bytes) => bytes,
+        Err(_) => return false,
+    };
+    let signature = Signature::from_bytes(&signature_bytes);
+
+    let configured_keys = trusted_keys.iter().filter_map(|key| {
+        let key_bytes = base64::engine::general_purpose::STANDARD.decode(key).ok()?;
+        let key_bytes: [u8; 32] = key_bytes.try_into().ok()?;
+        VerifyingKey::from_bytes(&key_bytes).ok()
+    });
+
+    let fallback_key = VerifyingKey::from_bytes(&TRUSTED_PUBLIC_KEY).ok();
+    configured_keys
+        .chain(fallback_key.filter(|_| trusted_keys.is_empty()))
+        .any(|key| key.verify_strict(bytes, &signature).is_ok())
 }
 
-fn is_wasm_file(file_name: &str) -> bool {
-    let mut file = match File::open(format!("../staging/{}", file_name)) {
+fn is_wasm_file(file_name: &str, staging_dir: &str) -> bool {
+    let mut file = match File::open(format!("{}/{}", staging_dir, file_name)) {
         Ok(file) => file,
         Err(_) => return false,
     };
@@ -492,3 +990,51 @@ fn is_wasm_file(file_name: &str) -> bool {
     };
     magic_number == [0x00, 0x61, 0x73, 0x6d]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_data_reassembles_to_the_original_bytes() {
+        // Deterministic pseudo-random data, large enough to span several
+        // chunk boundaries at the ~16 KiB average chunk size.
+        let mut data = Vec::with_capacity(200 * 1024);
+        let mut seed: u64 = 0x1234_5678_9abc_def0;
+        for _ in 0..data.capacity() {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            data.push((seed >> 56) as u8);
+        }
+
+        let chunks = chunk_data(&data);
+        let reassembled: Vec<u8> = chunks.iter().flat_map(|chunk| chunk.iter().copied()).collect();
+        assert_eq!(reassembled, data);
+    }
+
+    #[test]
+    fn chunk_data_respects_min_and_max_chunk_size() {
+        let data = vec![0xAB; 500 * 1024];
+        let chunks = chunk_data(&data);
+        assert!(chunks.len() > 1, "expected more than one chunk for 500 KiB of input");
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.len() >= CHUNK_MIN_SIZE);
+            assert!(chunk.len() <= CHUNK_MAX_SIZE);
+        }
+    }
+
+    #[test]
+    fn chunk_data_is_stable_for_a_known_byte_sequence() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i % 251) as u8).collect();
+        let chunk_lens: Vec<usize> = chunk_data(&data).iter().map(|chunk| chunk.len()).collect();
+        assert_eq!(chunk_lens, vec![20_000]);
+    }
+
+    #[test]
+    fn digest_hex_matches_known_sha256() {
+        // SHA-256("") — a stable known-answer test independent of chunking.
+        assert_eq!(
+            digest_hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}